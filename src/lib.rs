@@ -1,8 +1,26 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub mod atomic;
 pub mod pooled;
 
+/// Width in bits of the timestamp field in the default layout.
+const DEFAULT_TIMESTAMP_BITS: u32 = 41;
+
+/// Width in bits of the instance field in the default layout.
+const DEFAULT_INSTANCE_BITS: u32 = 10;
+
+/// Width in bits of the sequence field in the default layout.
+const DEFAULT_SEQUENCE_BITS: u32 = 12;
+
+/// Width in bits of the worker-id sub-field within the instance field, used by
+/// [`SnowflakeIdGen::with_datacenter`].
+const DATACENTER_WORKER_BITS: u32 = DEFAULT_INSTANCE_BITS / 2;
+
+/// Width in bits of the datacenter-id sub-field within the instance field, used by
+/// [`SnowflakeIdGen::with_datacenter`].
+const DATACENTER_DATACENTER_BITS: u32 = DEFAULT_INSTANCE_BITS - DATACENTER_WORKER_BITS;
+
 /// The `SnowflakeIdGen` type is snowflake algorithm wrapper.
 #[derive(Copy, Clone, Debug)]
 pub struct SnowflakeIdGen {
@@ -16,7 +34,24 @@ pub struct SnowflakeIdGen {
     pub instance: i32,
 
     /// auto-increment record.
-    idx: u16,
+    idx: u32,
+
+    /// shift applied to `instance` when composing an id, equal to `sequence_bits`.
+    instance_shift: u32,
+
+    /// shift applied to the timestamp when composing an id, equal to `instance_bits + sequence_bits`.
+    timestamp_shift: u32,
+
+    /// largest value `instance` can hold given `instance_bits`.
+    max_instance: i32,
+
+    /// largest value `idx` can hold given `sequence_bits`, i.e. ids issuable per millisecond.
+    max_sequence: u32,
+
+    /// When set, the `Instant` and millis-since-epoch captured at construction time, used to
+    /// derive the current millis without ever going backwards, instead of trusting the wall
+    /// clock. See [`SnowflakeIdGenBuilder::monotonic`].
+    monotonic_origin: Option<(Instant, i64)>,
 }
 
 impl SnowflakeIdGen {
@@ -25,30 +60,118 @@ impl SnowflakeIdGen {
     }
 
     pub fn with_epoch(instance: i32, epoch: SystemTime) -> SnowflakeIdGen {
-        //TODO:limit the maximum of input args machine_id and node_id
-        let last_time_millis = get_time_millis(epoch);
+        SnowflakeIdGen::builder()
+            .instance(instance)
+            .epoch(epoch)
+            .build()
+            .expect("the default bit widths always add up to 64 bits")
+    }
 
-        SnowflakeIdGen {
-            epoch,
-            last_time_millis,
-            instance,
-            idx: 0,
+    /// Like [`SnowflakeIdGen::with_epoch`], but the generated timestamp is derived from a
+    /// captured [`Instant`] rather than re-reading [`SystemTime::now`] every time, so it can
+    /// never be observed to go backwards (e.g. from an NTP correction or VM migration).
+    pub fn with_epoch_monotonic(instance: i32, epoch: SystemTime) -> SnowflakeIdGen {
+        SnowflakeIdGen::builder()
+            .instance(instance)
+            .epoch(epoch)
+            .monotonic(true)
+            .build()
+            .expect("the default bit widths always add up to 64 bits")
+    }
+
+    /// Like [`SnowflakeIdGen::with_epoch`], but splits the instance field into a `datacenter_id`
+    /// and a `worker_id` sub-field instead of one opaque value, so multi-region clusters can
+    /// assign ids without a central coordinator. Each sub-field gets half of the default 10-bit
+    /// instance field, i.e. 5 bits, capping both at 31.
+    ///
+    /// Returns an [`Error`] if either id doesn't fit in its sub-field.
+    pub fn with_datacenter(
+        datacenter_id: i32,
+        worker_id: i32,
+        epoch: SystemTime,
+    ) -> Result<SnowflakeIdGen, Error> {
+        let max_datacenter_id = (1 << DATACENTER_DATACENTER_BITS) - 1;
+        if !(0..=max_datacenter_id).contains(&datacenter_id) {
+            return Err(Error::DatacenterIdOutOfRange {
+                datacenter_id,
+                max: max_datacenter_id,
+            });
+        }
+
+        let max_worker_id = (1 << DATACENTER_WORKER_BITS) - 1;
+        if !(0..=max_worker_id).contains(&worker_id) {
+            return Err(Error::WorkerIdOutOfRange {
+                worker_id,
+                max: max_worker_id,
+            });
+        }
+
+        let instance = (datacenter_id << DATACENTER_WORKER_BITS) | worker_id;
+
+        Ok(SnowflakeIdGen::with_epoch(instance, epoch))
+    }
+
+    /// Starts building a [`SnowflakeIdGen`] with a custom epoch and/or bit-field layout.
+    ///
+    /// See [`SnowflakeIdGenBuilder`] for the available options and their defaults.
+    pub fn builder() -> SnowflakeIdGenBuilder {
+        SnowflakeIdGenBuilder::new()
+    }
+
+    pub fn generate(&mut self) -> Result<i64, GenerateError> {
+        match self.monotonic_origin {
+            Some((start_instant, start_millis)) => self.generate_with_millis_fn(move |_| {
+                start_millis + start_instant.elapsed().as_millis() as i64
+            }),
+            None => self.generate_with_millis_fn(get_time_millis),
         }
     }
 
-    pub fn generate(&mut self) -> Option<i64> {
-        self.generate_with_millis_fn(get_time_millis)
+    /// Like [`SnowflakeIdGen::generate`], but never fails on sequence exhaustion: it spins until
+    /// the clock advances and then retries, instead of leaving the caller to loop, sleep and
+    /// retry by hand. A clock rollback is handled the same way, by waiting for real time to
+    /// catch back up.
+    pub fn generate_blocking(&mut self) -> i64 {
+        let now_millis = |gen: &Self| match gen.monotonic_origin {
+            Some((start_instant, start_millis)) => {
+                start_millis + start_instant.elapsed().as_millis() as i64
+            }
+            None => get_time_millis(gen.epoch),
+        };
+
+        loop {
+            match self.generate() {
+                Ok(id) => return id,
+                Err(GenerateError::SequenceExhausted) => {
+                    while now_millis(self) <= self.last_time_millis {
+                        std::hint::spin_loop();
+                    }
+                }
+                Err(GenerateError::ClockRollback { .. }) => {
+                    while now_millis(self) < self.last_time_millis {
+                        std::hint::spin_loop();
+                    }
+                }
+            }
+        }
     }
 
-    fn generate_with_millis_fn<F>(&mut self, f: F) -> Option<i64>
+    fn generate_with_millis_fn<F>(&mut self, f: F) -> Result<i64, GenerateError>
     where
         F: Fn(SystemTime) -> i64,
     {
         let now_millis = f(self.epoch);
 
+        if now_millis < self.last_time_millis {
+            return Err(GenerateError::ClockRollback {
+                last_time_millis: self.last_time_millis,
+                now_millis,
+            });
+        }
+
         if now_millis == self.last_time_millis {
-            if self.idx >= 4095 {
-                return None;
+            if self.idx >= self.max_sequence {
+                return Err(GenerateError::SequenceExhausted);
             }
         } else {
             self.last_time_millis = now_millis;
@@ -57,7 +180,275 @@ impl SnowflakeIdGen {
 
         self.idx += 1;
 
-        Some(self.last_time_millis << 22 | ((self.instance << 12) as i64) | (self.idx as i64))
+        Ok(
+            self.last_time_millis << self.timestamp_shift
+                | ((self.instance << self.instance_shift) as i64)
+                | (self.idx as i64),
+        )
+    }
+
+    /// Reverses [`SnowflakeIdGen::generate`], recovering the timestamp, instance and sequence
+    /// that were encoded into `id`, using this generator's bit-field layout.
+    pub fn decode(&self, id: i64) -> DecodedSnowflake {
+        let timestamp_millis = id >> self.timestamp_shift;
+        let instance = ((id >> self.instance_shift) & self.max_instance as i64) as i32;
+        let sequence = (id & self.max_sequence as i64) as u32;
+
+        DecodedSnowflake {
+            timestamp_millis,
+            timestamp: self.epoch + Duration::from_millis(timestamp_millis as u64),
+            instance,
+            sequence,
+        }
+    }
+
+    /// Like [`SnowflakeIdGen::decode`], but additionally splits the instance field back into the
+    /// `datacenter_id` and `worker_id` it was constructed with by [`SnowflakeIdGen::with_datacenter`].
+    pub fn decode_datacenter(&self, id: i64) -> DecodedDatacenterSnowflake {
+        let decoded = self.decode(id);
+
+        let datacenter_id = decoded.instance >> DATACENTER_WORKER_BITS;
+        let worker_id = decoded.instance & ((1 << DATACENTER_WORKER_BITS) - 1);
+
+        DecodedDatacenterSnowflake {
+            timestamp_millis: decoded.timestamp_millis,
+            timestamp: decoded.timestamp,
+            datacenter_id,
+            worker_id,
+            sequence: decoded.sequence,
+        }
+    }
+}
+
+/// Builds a [`SnowflakeIdGen`] with a custom epoch and bit-field layout.
+///
+/// The defaults reproduce the original, fixed layout: a 41-bit timestamp, a 10-bit instance and
+/// a 12-bit sequence, capping instances at 1023 and ids-per-millisecond at 4095.
+#[derive(Copy, Clone, Debug)]
+pub struct SnowflakeIdGenBuilder {
+    epoch: SystemTime,
+    instance: i32,
+    timestamp_bits: u32,
+    instance_bits: u32,
+    sequence_bits: u32,
+    monotonic: bool,
+}
+
+impl SnowflakeIdGenBuilder {
+    fn new() -> Self {
+        SnowflakeIdGenBuilder {
+            epoch: UNIX_EPOCH,
+            instance: 0,
+            timestamp_bits: DEFAULT_TIMESTAMP_BITS,
+            instance_bits: DEFAULT_INSTANCE_BITS,
+            sequence_bits: DEFAULT_SEQUENCE_BITS,
+            monotonic: false,
+        }
+    }
+
+    /// Sets the epoch the generated timestamps are relative to. Defaults to [`UNIX_EPOCH`].
+    pub fn epoch(mut self, epoch: SystemTime) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Sets the instance (machine/sectionalization) id embedded in generated ids. Defaults to `0`.
+    pub fn instance(mut self, instance: i32) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    /// Sets the width in bits of the timestamp field. Defaults to 41.
+    pub fn timestamp_bits(mut self, bits: u32) -> Self {
+        self.timestamp_bits = bits;
+        self
+    }
+
+    /// Sets the width in bits of the instance field. Defaults to 10.
+    pub fn instance_bits(mut self, bits: u32) -> Self {
+        self.instance_bits = bits;
+        self
+    }
+
+    /// Sets the width in bits of the sequence field. Defaults to 12.
+    pub fn sequence_bits(mut self, bits: u32) -> Self {
+        self.sequence_bits = bits;
+        self
+    }
+
+    /// When `true`, the generator derives its timestamp from a captured [`Instant`] instead of
+    /// re-reading [`SystemTime::now`], so it can never observe the clock moving backwards.
+    /// Defaults to `false`.
+    pub fn monotonic(mut self, monotonic: bool) -> Self {
+        self.monotonic = monotonic;
+        self
+    }
+
+    /// Builds the generator, validating that the sign bit plus the three field widths add up to
+    /// exactly the 64 bits available in an `i64`.
+    pub fn build(self) -> Result<SnowflakeIdGen, Error> {
+        if 1 + self.timestamp_bits + self.instance_bits + self.sequence_bits != 64 {
+            return Err(Error::InvalidBitWidths {
+                timestamp_bits: self.timestamp_bits,
+                instance_bits: self.instance_bits,
+                sequence_bits: self.sequence_bits,
+            });
+        }
+
+        let instance_shift = self.sequence_bits;
+        let timestamp_shift = self.instance_bits + self.sequence_bits;
+
+        let last_time_millis = get_time_millis(self.epoch);
+        let monotonic_origin = self.monotonic.then(|| (Instant::now(), last_time_millis));
+
+        Ok(SnowflakeIdGen {
+            last_time_millis,
+            epoch: self.epoch,
+            instance: self.instance,
+            idx: 0,
+            instance_shift,
+            timestamp_shift,
+            max_instance: (1 << self.instance_bits) - 1,
+            max_sequence: (1 << self.sequence_bits) - 1,
+            monotonic_origin,
+        })
+    }
+}
+
+impl Default for SnowflakeIdGenBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Errors produced while configuring a [`SnowflakeIdGen`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The configured bit widths did not add up to the 64 bits available in an `i64`, once the
+    /// leading sign bit is accounted for.
+    InvalidBitWidths {
+        timestamp_bits: u32,
+        instance_bits: u32,
+        sequence_bits: u32,
+    },
+
+    /// The `datacenter_id` passed to [`SnowflakeIdGen::with_datacenter`] didn't fit in its 5-bit
+    /// sub-field.
+    DatacenterIdOutOfRange { datacenter_id: i32, max: i32 },
+
+    /// The `worker_id` passed to [`SnowflakeIdGen::with_datacenter`] didn't fit in its 5-bit
+    /// sub-field.
+    WorkerIdOutOfRange { worker_id: i32, max: i32 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidBitWidths {
+                timestamp_bits,
+                instance_bits,
+                sequence_bits,
+            } => write!(
+                f,
+                "1 (sign bit) + timestamp_bits ({timestamp_bits}) + instance_bits ({instance_bits}) + sequence_bits ({sequence_bits}) must equal 64"
+            ),
+            Error::DatacenterIdOutOfRange { datacenter_id, max } => {
+                write!(f, "datacenter_id ({datacenter_id}) must be between 0 and {max}")
+            }
+            Error::WorkerIdOutOfRange { worker_id, max } => {
+                write!(f, "worker_id ({worker_id}) must be between 0 and {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Errors produced by [`SnowflakeIdGen::generate`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GenerateError {
+    /// All sequence numbers for the current millisecond are in use. Retry on (or after) the next
+    /// millisecond, or use [`SnowflakeIdGen::generate_blocking`].
+    SequenceExhausted,
+
+    /// The clock moved backwards compared to the last generated id, e.g. from an NTP correction
+    /// or a VM migration. Generating an id here would risk colliding with, or preceding, one
+    /// already issued; use [`SnowflakeIdGen::with_epoch_monotonic`] to avoid this entirely.
+    ClockRollback {
+        last_time_millis: i64,
+        now_millis: i64,
+    },
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::SequenceExhausted => {
+                write!(f, "all sequence numbers for the current millisecond are in use")
+            }
+            GenerateError::ClockRollback {
+                last_time_millis,
+                now_millis,
+            } => write!(
+                f,
+                "clock moved backwards: last generated id used millisecond {last_time_millis}, but the clock now reads {now_millis}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// The parts of a snowflake id, as recovered by [`SnowflakeIdGen::decode`] or
+/// [`decode_with_epoch`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodedSnowflake {
+    /// Milliseconds since the epoch the id was generated with.
+    pub timestamp_millis: i64,
+
+    /// The point in time the id was generated, reconstructed from `epoch + timestamp_millis`.
+    pub timestamp: SystemTime,
+
+    /// The `instance` the id was generated with.
+    pub instance: i32,
+
+    /// The auto-increment sequence number within `timestamp_millis`.
+    pub sequence: u32,
+}
+
+/// The parts of a snowflake id generated by [`SnowflakeIdGen::with_datacenter`], as recovered by
+/// [`SnowflakeIdGen::decode_datacenter`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodedDatacenterSnowflake {
+    /// Milliseconds since the epoch the id was generated with.
+    pub timestamp_millis: i64,
+
+    /// The point in time the id was generated, reconstructed from `epoch + timestamp_millis`.
+    pub timestamp: SystemTime,
+
+    /// The datacenter id the id was generated with.
+    pub datacenter_id: i32,
+
+    /// The worker id the id was generated with.
+    pub worker_id: i32,
+
+    /// The auto-increment sequence number within `timestamp_millis`.
+    pub sequence: u32,
+}
+
+/// Decodes a snowflake `id` that was generated with the given `epoch` and the default bit-field
+/// layout (41-bit timestamp, 10-bit instance, 12-bit sequence), without needing the original
+/// [`SnowflakeIdGen`] that created it.
+pub fn decode_with_epoch(id: i64, epoch: SystemTime) -> DecodedSnowflake {
+    let timestamp_millis = id >> 22;
+    let instance = ((id >> 12) & 0x3FF) as i32;
+    let sequence = (id & 0xFFF) as u32;
+
+    DecodedSnowflake {
+        timestamp_millis,
+        timestamp: epoch + Duration::from_millis(timestamp_millis as u64),
+        instance,
+        sequence,
     }
 }
 
@@ -109,7 +500,7 @@ mod tests {
             .map(|cycle| loop {
                 let mut lock = generator.lock().unwrap();
 
-                if let Some(id) = lock.generate() {
+                if let Ok(id) = lock.generate() {
                     break id;
                 }
                 println!("Thread {thread} Cycle {cycle}: idx for current time has been filled!");
@@ -126,9 +517,132 @@ mod tests {
 
         for _ in 1..=4095 {
             let id = generator.generate_with_millis_fn(|_| 0);
-            assert!(matches!(id, Some(_)));
+            assert!(id.is_ok());
         }
 
-        assert_eq!(generator.generate_with_millis_fn(|_| 0), None);
+        assert_eq!(
+            generator.generate_with_millis_fn(|_| 0),
+            Err(GenerateError::SequenceExhausted)
+        );
+    }
+
+    #[test]
+    fn clock_rollback_is_rejected() {
+        let mut generator = SnowflakeIdGen::with_epoch(0, SystemTime::now());
+
+        generator.generate_with_millis_fn(|_| 100).unwrap();
+
+        assert_eq!(
+            generator.generate_with_millis_fn(|_| 50),
+            Err(GenerateError::ClockRollback {
+                last_time_millis: 100,
+                now_millis: 50,
+            })
+        );
+    }
+
+    #[test]
+    fn monotonic_generator_never_observes_rollback() {
+        let mut generator = SnowflakeIdGen::with_epoch_monotonic(0, SystemTime::now());
+
+        for _ in 0..10 {
+            assert!(generator.generate().is_ok());
+        }
+    }
+
+    #[test]
+    fn decode_recovers_generate_input() {
+        let mut generator = SnowflakeIdGen::with_epoch(42, SystemTime::now());
+
+        let id = generator.generate_with_millis_fn(|_| 1337).unwrap();
+        let decoded = generator.decode(id);
+
+        assert_eq!(decoded.timestamp_millis, 1337);
+        assert_eq!(decoded.timestamp, generator.epoch + Duration::from_millis(1337));
+        assert_eq!(decoded.instance, 42);
+        assert_eq!(decoded.sequence, 1);
+    }
+
+    #[test]
+    fn builder_rejects_mismatched_bit_widths() {
+        let result = SnowflakeIdGen::builder()
+            .timestamp_bits(40)
+            .instance_bits(10)
+            .sequence_bits(12)
+            .build();
+
+        assert_eq!(
+            result.unwrap_err(),
+            Error::InvalidBitWidths {
+                timestamp_bits: 40,
+                instance_bits: 10,
+                sequence_bits: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn builder_supports_custom_bit_widths() {
+        let mut generator = SnowflakeIdGen::builder()
+            .instance(3)
+            .epoch(SystemTime::now())
+            .timestamp_bits(44)
+            .instance_bits(2)
+            .sequence_bits(17)
+            .build()
+            .unwrap();
+
+        let id = generator.generate_with_millis_fn(|_| 1337).unwrap();
+        let decoded = generator.decode(id);
+
+        assert_eq!(decoded.timestamp_millis, 1337);
+        assert_eq!(decoded.instance, 3);
+        assert_eq!(decoded.sequence, 1);
+    }
+
+    #[test]
+    fn generate_blocking_never_returns_none() {
+        let mut generator = SnowflakeIdGen::with_epoch(0, SystemTime::now());
+
+        let mut ids = (0..4100)
+            .map(|_| generator.generate_blocking())
+            .collect::<Vec<_>>();
+
+        ids.sort();
+        ids.dedup();
+
+        assert_eq!(ids.len(), 4100);
+    }
+
+    #[test]
+    fn with_datacenter_packs_and_decodes_sub_fields() {
+        let mut generator = SnowflakeIdGen::with_datacenter(9, 17, SystemTime::now()).unwrap();
+
+        let id = generator.generate_with_millis_fn(|_| 1337).unwrap();
+        let decoded = generator.decode_datacenter(id);
+
+        assert_eq!(decoded.timestamp_millis, 1337);
+        assert_eq!(decoded.datacenter_id, 9);
+        assert_eq!(decoded.worker_id, 17);
+        assert_eq!(decoded.sequence, 1);
+    }
+
+    #[test]
+    fn with_datacenter_rejects_out_of_range_ids() {
+        assert_eq!(
+            SnowflakeIdGen::with_datacenter(32, 0, SystemTime::now()).unwrap_err(),
+            Error::DatacenterIdOutOfRange {
+                datacenter_id: 32,
+                max: 31,
+            }
+        );
+
+        assert_eq!(
+            SnowflakeIdGen::with_datacenter(0, 32, SystemTime::now()).unwrap_err(),
+            Error::WorkerIdOutOfRange {
+                worker_id: 32,
+                max: 31,
+            }
+        );
     }
 }